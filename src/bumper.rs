@@ -0,0 +1,615 @@
+use crate::errors::MigrationError;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+const JOURNAL_FILE_NAME: &str = ".bump_migrations.log";
+
+/// A single django migration file, identified by its leading prefix token
+/// (e.g. `"0001"` or `"20240115120000"`) and its full file name. `sort_key`
+/// is the prefix parsed as a number, used for ordering; `prefix` is kept
+/// verbatim so its width and format can be preserved when bumping.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub prefix: String,
+    pub sort_key: i64,
+    pub name: String,
+}
+
+/// A problem found while validating the migration history of a directory.
+#[derive(Debug, Clone)]
+pub enum ValidationIssue {
+    Duplicate(String),
+    Hole { after: String, before: String },
+    DanglingDependency {
+        migration: String,
+        dependency: String,
+    },
+    ForwardDependency {
+        migration: String,
+        dependency: String,
+    },
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationIssue::Duplicate(prefix) => {
+                write!(f, "duplicate migration number: {}", prefix)
+            }
+            ValidationIssue::Hole { after, before } => {
+                write!(f, "hole in migration numbering: {} -> {}", after, before)
+            }
+            ValidationIssue::DanglingDependency {
+                migration,
+                dependency,
+            } => write!(
+                f,
+                "{} depends on {}, which does not exist",
+                migration, dependency
+            ),
+            ValidationIssue::ForwardDependency {
+                migration,
+                dependency,
+            } => write!(
+                f,
+                "{} depends on {}, which comes after it",
+                migration, dependency
+            ),
+        }
+    }
+}
+
+pub fn collect_migrations(path: &str) -> Result<Vec<Migration>, MigrationError> {
+    let files =
+        fs::read_dir(path).map_err(|_| MigrationError::DirNotFound(String::from(path)))?;
+
+    let mut migrations: Vec<Migration> = vec![];
+    for file in files {
+        let entry = file?;
+        let file_name = match entry.path().file_name().and_then(|n| n.to_str()) {
+            Some(name) => String::from(name),
+            None => continue,
+        };
+        let prefix = file_name.split("_").next().unwrap_or("");
+        match prefix.parse::<i64>() {
+            Ok(sort_key) => migrations.push(Migration {
+                prefix: String::from(prefix),
+                sort_key,
+                name: file_name,
+            }),
+            Err(_) => println!("Not a migration file, carry on: ({})", file_name),
+        };
+    }
+
+    Ok(migrations)
+}
+
+/// Computes the prefix a migration should get once appended after the
+/// current highest-numbered one in `migrations` (which must already be
+/// sorted by `sort_key`): one greater than the highest `sort_key`,
+/// zero-padded to the same width as the highest migration's prefix. This
+/// keeps `0001_`-style zero padding and `YYYYMMDDHHMMSS`-style timestamps
+/// correctly formatted instead of producing a bare integer.
+pub fn next_prefix(migrations: &[Migration]) -> Option<String> {
+    let highest = migrations.last()?;
+    Some(format!(
+        "{:0width$}",
+        highest.sort_key + 1,
+        width = highest.prefix.len()
+    ))
+}
+
+/// A single entry in a migration's `dependencies = [...]` list, e.g.
+/// `('app', '0003_thing')`.
+#[derive(Debug, Clone)]
+pub struct DependencyTuple {
+    pub app_label: String,
+    pub migration_name: String,
+}
+
+/// A quoted string literal found while scanning a `dependencies` block,
+/// together with its byte range within the slice it was found in. The
+/// quote character itself is never rewritten, only the text inside it, so
+/// callers don't need to know which one was used.
+struct QuotedSpan<'a> {
+    value: &'a str,
+    start: usize,
+    end: usize,
+}
+
+fn find_quoted_string(s: &str) -> Option<QuotedSpan<'_>> {
+    let start = s.find(['\'', '"'])?;
+    let quote = s[start..].chars().next().unwrap();
+    let rest = &s[start + 1..];
+    let end_rel = rest.find(quote)?;
+    Some(QuotedSpan {
+        value: &rest[..end_rel],
+        start,
+        end: start + 1 + end_rel + 1,
+    })
+}
+
+/// Finds the byte range of the `[ ... ]` list following a `dependencies`
+/// assignment, brackets included.
+fn find_dependencies_block(contents: &str) -> Option<(usize, usize)> {
+    let keyword = contents.find("dependencies")?;
+    let bracket_start = keyword + contents[keyword..].find('[')?;
+
+    let mut depth = 0;
+    for (offset, c) in contents[bracket_start..].char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((bracket_start, bracket_start + offset));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses every `('app_label', 'NNNN_name')` tuple out of a migration
+/// file's `dependencies = [...]` block. Returns an empty vec if the file
+/// declares no dependencies.
+pub fn parse_dependencies(contents: &str) -> Vec<DependencyTuple> {
+    let (block_start, block_end) = match find_dependencies_block(contents) {
+        Some(range) => range,
+        None => return vec![],
+    };
+
+    let mut tuples = vec![];
+    let mut pos = block_start + 1;
+    while pos < block_end {
+        let open_rel = match contents[pos..block_end].find('(') {
+            Some(i) => i,
+            None => break,
+        };
+        let tuple_open = pos + open_rel;
+        let close_rel = match contents[tuple_open..block_end].find(')') {
+            Some(i) => i,
+            None => break,
+        };
+        let tuple_close = tuple_open + close_rel;
+        let tuple_body = &contents[tuple_open + 1..tuple_close];
+
+        if let Some(app_span) = find_quoted_string(tuple_body) {
+            let after_app = &tuple_body[app_span.end..];
+            if let Some(name_span) = find_quoted_string(after_app) {
+                tuples.push(DependencyTuple {
+                    app_label: String::from(app_span.value),
+                    migration_name: String::from(name_span.value),
+                });
+            }
+        }
+
+        pos = tuple_close + 1;
+    }
+
+    tuples
+}
+
+/// Rewrites the `dependencies = [...]` tuple whose migration name matches
+/// `predecessor_name`, replacing that name with `new_name`. The app label,
+/// quoting style, and surrounding formatting are left untouched, so this
+/// also does the right thing for migrations that depend on more than one
+/// parent: only the matching tuple is touched.
+pub fn rewrite_dependency(
+    contents: &str,
+    migration: &str,
+    predecessor_name: &str,
+    new_name: &str,
+) -> Result<String, MigrationError> {
+    let predecessor_name = predecessor_name.trim_end_matches(".py");
+    let new_name = new_name.trim_end_matches(".py");
+
+    let (block_start, block_end) =
+        find_dependencies_block(contents).ok_or_else(|| MigrationError::DependencyNotFound {
+            migration: String::from(migration),
+            predecessor: String::from(predecessor_name),
+        })?;
+
+    let mut pos = block_start + 1;
+    while pos < block_end {
+        let open_rel = match contents[pos..block_end].find('(') {
+            Some(i) => i,
+            None => break,
+        };
+        let tuple_open = pos + open_rel;
+        let close_rel = match contents[tuple_open..block_end].find(')') {
+            Some(i) => i,
+            None => break,
+        };
+        let tuple_close = tuple_open + close_rel;
+        let tuple_body = &contents[tuple_open + 1..tuple_close];
+
+        if let Some(app_span) = find_quoted_string(tuple_body) {
+            let after_app = &tuple_body[app_span.end..];
+            if let Some(name_span) = find_quoted_string(after_app) {
+                if name_span.value == predecessor_name {
+                    let value_start = tuple_open + 1 + app_span.end + name_span.start + 1;
+                    let value_end = value_start + name_span.value.len();
+                    let mut rewritten = String::with_capacity(contents.len());
+                    rewritten.push_str(&contents[..value_start]);
+                    rewritten.push_str(new_name);
+                    rewritten.push_str(&contents[value_end..]);
+                    return Ok(rewritten);
+                }
+            }
+        }
+
+        pos = tuple_close + 1;
+    }
+
+    Err(MigrationError::DependencyNotFound {
+        migration: String::from(migration),
+        predecessor: String::from(predecessor_name),
+    })
+}
+
+/// Django migration timestamps are 14-digit `YYYYMMDDHHMMSS` prefixes, far
+/// wider than any realistic sequential numbering scheme (`0001`, `000123`,
+/// ...). Prefixes at least this wide are treated as timestamps rather than
+/// an ordinal sequence, so they're exempt from the "+1" contiguity check.
+const TIMESTAMP_PREFIX_LEN: usize = 14;
+
+fn is_timestamp_prefix(prefix: &str) -> bool {
+    prefix.len() >= TIMESTAMP_PREFIX_LEN
+}
+
+/// Derives this app's label from its migrations directory path, assuming
+/// the conventional Django layout `<app>/migrations/`. Used to tell
+/// same-app dependencies (which must resolve to a file in `path`) apart
+/// from cross-app dependencies (which live in a directory this tool was
+/// never pointed at, so their absence here doesn't mean they're dangling).
+fn this_app_label(path: &str) -> Option<String> {
+    std::path::Path::new(path.trim_end_matches('/'))
+        .parent()?
+        .file_name()?
+        .to_str()
+        .map(String::from)
+}
+
+/// Validates the migration history in `path`, reporting duplicate numbers,
+/// holes in the numbering sequence, and dependencies that point at a
+/// migration that doesn't exist or comes later than the migration declaring
+/// it. Returns a list of issues; an empty list means the history is clean.
+///
+/// The hole check only applies between two fixed-width integer prefixes:
+/// timestamp-style prefixes are never exactly one apart, so they're skipped
+/// rather than flagged as gaps. Dependency checks are likewise scoped to
+/// this app: a dependency naming another app's migration is resolved in a
+/// directory `validate` was never given, so it's left alone rather than
+/// reported as dangling.
+pub fn validate(path: &str) -> Result<Vec<ValidationIssue>, MigrationError> {
+    let mut migrations = collect_migrations(path)?;
+    migrations.sort_by_key(|m| m.sort_key);
+
+    let mut issues = vec![];
+
+    for pair in migrations.windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        if next.sort_key == prev.sort_key {
+            issues.push(ValidationIssue::Duplicate(next.prefix.clone()));
+        } else if next.sort_key != prev.sort_key + 1
+            && !is_timestamp_prefix(&prev.prefix)
+            && !is_timestamp_prefix(&next.prefix)
+        {
+            issues.push(ValidationIssue::Hole {
+                after: prev.prefix.clone(),
+                before: next.prefix.clone(),
+            });
+        }
+    }
+
+    let this_app = this_app_label(path);
+
+    for migration in &migrations {
+        let file_path = [path, &migration.name].join("");
+        let contents = match fs::read_to_string(file_path) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+
+        for dependency in parse_dependencies(&contents) {
+            if let Some(this_app) = &this_app {
+                if &dependency.app_label != this_app {
+                    // Cross-app dependency: it lives in a directory this
+                    // call was never pointed at, so we can't resolve it
+                    // here and shouldn't flag it as dangling.
+                    continue;
+                }
+            }
+
+            let dependency = dependency.migration_name;
+            match migrations
+                .iter()
+                .find(|m| m.name.trim_end_matches(".py") == dependency)
+            {
+                Some(found) if found.sort_key >= migration.sort_key => {
+                    issues.push(ValidationIssue::ForwardDependency {
+                        migration: migration.name.clone(),
+                        dependency,
+                    });
+                }
+                None => {
+                    issues.push(ValidationIssue::DanglingDependency {
+                        migration: migration.name.clone(),
+                        dependency,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Renames a migration file within `path`, used by both `bump` and `undo`.
+pub fn rename_migration_file(path: &str, from: &str, to: &str) -> Result<(), MigrationError> {
+    fs::rename(format!("{}{}", path, from), format!("{}{}", path, to))?;
+    Ok(())
+}
+
+/// A record of one bump, written to `.bump_migrations.log` so it can be
+/// reversed exactly by `undo`, even after several further bumps have
+/// happened in between.
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub original_name: String,
+    pub new_name: String,
+    pub old_dependency: String,
+    pub new_dependency: String,
+}
+
+impl JournalEntry {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}",
+            self.original_name, self.new_name, self.old_dependency, self.new_dependency
+        )
+    }
+
+    fn from_line(line: &str) -> Result<JournalEntry, MigrationError> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            [original_name, new_name, old_dependency, new_dependency] => Ok(JournalEntry {
+                original_name: String::from(*original_name),
+                new_name: String::from(*new_name),
+                old_dependency: String::from(*old_dependency),
+                new_dependency: String::from(*new_dependency),
+            }),
+            _ => Err(MigrationError::MalformedJournalEntry(String::from(line))),
+        }
+    }
+}
+
+fn journal_path(path: &str) -> String {
+    format!("{}{}", path, JOURNAL_FILE_NAME)
+}
+
+/// Appends one entry to `.bump_migrations.log`, creating it if needed.
+pub fn append_journal_entry(path: &str, entry: &JournalEntry) -> Result<(), MigrationError> {
+    let mut journal = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path(path))?;
+    writeln!(journal, "{}", entry.to_line())?;
+    Ok(())
+}
+
+/// Reads every recorded journal entry, oldest first. Returns an empty vec
+/// if no bumps have been journaled yet.
+pub fn read_journal(path: &str) -> Result<Vec<JournalEntry>, MigrationError> {
+    let contents = match fs::read_to_string(journal_path(path)) {
+        Ok(c) => c,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(JournalEntry::from_line)
+        .collect()
+}
+
+/// Rewrites `.bump_migrations.log` with `entries`, oldest first.
+fn write_journal(path: &str, entries: &[JournalEntry]) -> Result<(), MigrationError> {
+    let mut data = String::new();
+    for entry in entries {
+        data.push_str(&entry.to_line());
+        data.push('\n');
+    }
+    fs::write(journal_path(path), data)?;
+    Ok(())
+}
+
+/// Reverses the most recent bump of `bumped_name`: renames the file back
+/// to its original number and restores its dependency tuple to point at
+/// the migration that preceded it before the bump, using the journal
+/// written by `bump_migration`.
+pub fn undo(path: &str, bumped_name: &str) -> Result<(), MigrationError> {
+    let mut entries = read_journal(path)?;
+    let entry_idx = entries
+        .iter()
+        .rposition(|entry| entry.new_name == bumped_name)
+        .ok_or_else(|| MigrationError::JournalEntryNotFound(String::from(bumped_name)))?;
+    let entry = entries[entry_idx].clone();
+
+    let file_path = [path, &entry.new_name].join("");
+    let contents = fs::read_to_string(&file_path)?;
+    let restored = rewrite_dependency(
+        &contents,
+        &entry.new_name,
+        &entry.new_dependency,
+        &entry.old_dependency,
+    )?;
+    fs::write(&file_path, restored)?;
+
+    rename_migration_file(path, &entry.new_name, &entry.original_name)?;
+
+    entries.remove(entry_idx);
+    write_journal(path, &entries)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn migration(prefix: &str, sort_key: i64) -> Migration {
+        Migration {
+            prefix: String::from(prefix),
+            sort_key,
+            name: format!("{}_test.py", prefix),
+        }
+    }
+
+    /// Creates a fresh, empty directory under the system temp dir and
+    /// returns its path with a trailing slash, matching the `path` format
+    /// the rest of this module expects.
+    fn temp_dir() -> String {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!(
+            "bump_migrations_test_{}_{}",
+            std::process::id(),
+            id
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        format!("{}/", dir.to_str().unwrap())
+    }
+
+    #[test]
+    fn parse_dependencies_reads_every_tuple_regardless_of_quote_style() {
+        let contents = r#"
+class Migration(migrations.Migration):
+    dependencies = [
+        ('blog', "0003_post"),
+        ('accounts', '0002_user'),
+    ]
+"#;
+        let deps = parse_dependencies(contents);
+        assert_eq!(deps.len(), 2);
+        assert_eq!(deps[0].app_label, "blog");
+        assert_eq!(deps[0].migration_name, "0003_post");
+        assert_eq!(deps[1].app_label, "accounts");
+        assert_eq!(deps[1].migration_name, "0002_user");
+    }
+
+    #[test]
+    fn parse_dependencies_returns_empty_without_a_dependencies_block() {
+        let contents = "class Migration(migrations.Migration):\n    operations = []\n";
+        assert!(parse_dependencies(contents).is_empty());
+    }
+
+    #[test]
+    fn rewrite_dependency_only_touches_the_matching_tuple() {
+        let contents = r#"dependencies = [
+        ('blog', '0003_post'),
+        ('accounts', "0002_user"),
+    ]
+"#;
+        let rewritten =
+            rewrite_dependency(contents, "0004_thing.py", "0003_post", "0010_post").unwrap();
+
+        assert!(rewritten.contains("('blog', '0010_post')"));
+        assert!(rewritten.contains(r#"('accounts', "0002_user")"#));
+    }
+
+    #[test]
+    fn rewrite_dependency_errors_when_no_tuple_matches() {
+        let contents = "dependencies = [('blog', '0003_post')]\n";
+        let result = rewrite_dependency(contents, "0004_thing.py", "0099_missing", "0010_post");
+        assert!(matches!(
+            result,
+            Err(MigrationError::DependencyNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn next_prefix_preserves_zero_padded_width() {
+        let migrations = vec![migration("0001", 1), migration("0002", 2)];
+        assert_eq!(next_prefix(&migrations).unwrap(), "0003");
+    }
+
+    #[test]
+    fn next_prefix_preserves_timestamp_width() {
+        let migrations = vec![
+            migration("20240101000000", 20240101000000),
+            migration("20240102000000", 20240102000000),
+        ];
+        assert_eq!(next_prefix(&migrations).unwrap(), "20240102000001");
+    }
+
+    #[test]
+    fn next_prefix_is_none_for_an_empty_history() {
+        assert!(next_prefix(&[]).is_none());
+    }
+
+    #[test]
+    fn validate_ignores_cross_app_dependencies() {
+        let base = temp_dir();
+        let app_dir = format!("{}app/migrations/", base);
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(
+            format!("{}0001_initial.py", app_dir),
+            "dependencies = [('auth', '0012_some_auth_migration')]\n",
+        )
+        .unwrap();
+
+        let issues = validate(&app_dir).unwrap();
+        assert!(issues.is_empty());
+
+        fs::remove_dir_all(base.trim_end_matches('/')).unwrap();
+    }
+
+    #[test]
+    fn validate_still_flags_dangling_same_app_dependencies() {
+        let base = temp_dir();
+        let app_dir = format!("{}app/migrations/", base);
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(
+            format!("{}0001_initial.py", app_dir),
+            "dependencies = [('app', '0099_missing')]\n",
+        )
+        .unwrap();
+
+        let issues = validate(&app_dir).unwrap();
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(
+            issues[0],
+            ValidationIssue::DanglingDependency { .. }
+        ));
+
+        fs::remove_dir_all(base.trim_end_matches('/')).unwrap();
+    }
+
+    #[test]
+    fn journal_round_trips_through_append_and_read() {
+        let path = temp_dir();
+        let entry = JournalEntry {
+            original_name: String::from("0001_init.py"),
+            new_name: String::from("0003_init.py"),
+            old_dependency: String::from("0000_none"),
+            new_dependency: String::from("0002_last"),
+        };
+        append_journal_entry(&path, &entry).unwrap();
+
+        let entries = read_journal(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].original_name, entry.original_name);
+        assert_eq!(entries[0].new_name, entry.new_name);
+        assert_eq!(entries[0].old_dependency, entry.old_dependency);
+        assert_eq!(entries[0].new_dependency, entry.new_dependency);
+
+        fs::remove_dir_all(path.trim_end_matches('/')).unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+use thiserror::Error;
+
+/// Everything that can go wrong while collecting, validating, or bumping a
+/// migration, surfaced as a proper error instead of a panic.
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("could not find migration directory '{0}'")]
+    DirNotFound(String),
+
+    #[error("'{0}' is not a migration file")]
+    NotAMigrationFile(String),
+
+    #[error("could not parse a migration number from '{0}'")]
+    MigrationNumberUnparseable(String),
+
+    #[error("no dependency tuple in '{migration}' points at '{predecessor}'")]
+    DependencyNotFound {
+        migration: String,
+        predecessor: String,
+    },
+
+    #[error("nothing precedes the first migration '{0}'")]
+    NoPredecessor(String),
+
+    #[error("refusing to bump into an already-inconsistent migration history")]
+    InconsistentHistory,
+
+    #[error("failed to write '{0}'")]
+    WriteFailed(String, #[source] std::io::Error),
+
+    #[error("no journal entry found for '{0}' in .bump_migrations.log")]
+    JournalEntryNotFound(String),
+
+    #[error("malformed journal entry: '{0}'")]
+    MalformedJournalEntry(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
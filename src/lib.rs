@@ -0,0 +1,2 @@
+pub mod bumper;
+pub mod errors;
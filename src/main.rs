@@ -1,180 +1,295 @@
-use bump_migrations::bumper::Migration;
-use std::error::Error;
+use bump_migrations::bumper::{
+    append_journal_entry, collect_migrations, next_prefix, rename_migration_file,
+    rewrite_dependency, undo, validate, JournalEntry, Migration,
+};
+use bump_migrations::errors::MigrationError;
+use clap::{Arg, ArgAction, Command};
 use std::fs::File;
 use std::io::{Read, Write};
-use std::panic::set_hook;
 use std::path::Path;
-use std::{env, fs};
-
-const INCORRECT_USAGE_MESSAGE: &str =
-    r#"Incorrect usage of bump_migrations, please see bump_migrations --help for more details"#;
-const HELP_MESSAGE: &str = r#"
-Bump_migrations is a simple program that bumps django migrations in proper order so that merge migrations can be avoided.
-
-USAGE:
-    bump_migrations [OPTIONS] <dir_path> <migration_name> 
-
-OPTIONS:
-    <dir_path>                 Path to the django migration folder.
-    <migration_name>           Name of the migration file to bump.
-
-FLAGS:
-    -h, --help             Print help information.
-"#;
-
-fn collect_migrations(path: &str) -> Vec<Migration> {
-    let files = fs::read_dir(path).expect("Could not find directory.");
-
-    let mut migrations: Vec<Migration> = vec![];
-    for file in files {
-        let file_name = String::from(file.unwrap().path().file_name().unwrap().to_str().unwrap());
-        let migration_number = file_name.split("_").nth(0).unwrap().parse::<i32>();
-        match migration_number {
-            Ok(value) => migrations.push(Migration {
-                number: value,
-                name: file_name,
-            }),
-            Err(_) => println!("Not a migration file, carry on: ({})", file_name),
+
+fn cli() -> Command {
+    Command::new("bump_migrations")
+        .about("Bumps django migrations to the end of the numbering order so merge migrations can be avoided.")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Print what bump would do without touching the filesystem"),
+        )
+        .subcommand(
+            Command::new("bump")
+                .about("Bump one or more migrations to the end of the history")
+                .arg(Arg::new("dir_path").required(true))
+                .arg(Arg::new("migration_names").required(true).num_args(1..)),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List migrations, sorted by number, alongside what each would be renamed to if bumped")
+                .arg(Arg::new("dir_path").required(true)),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check the migration history for gaps, duplicate numbers, and bad dependencies")
+                .arg(Arg::new("dir_path").required(true)),
+        )
+        .subcommand(
+            Command::new("undo")
+                .about("Reverse a previous bump")
+                .arg(Arg::new("dir_path").required(true))
+                .arg(Arg::new("bumped_name").required(true)),
+        )
+}
+
+/// Prints every migration in `path`, sorted by number, next to the name it
+/// would be given if it were bumped right now.
+fn list(path: &str) -> Result<(), MigrationError> {
+    let mut migrations = collect_migrations(path)?;
+    migrations.sort_by_key(|m| m.sort_key);
+
+    let new_prefix = next_prefix(&migrations);
+
+    println!("{:<40} IF BUMPED", "MIGRATION");
+    for migration in &migrations {
+        let bumped_name = match &new_prefix {
+            Some(new_prefix) => bumped_name(migration, new_prefix),
+            None => migration.name.clone(),
         };
+        println!("{:<40} {}", migration.name, bumped_name);
+    }
+
+    Ok(())
+}
+
+/// Renders what `migration` would be renamed to if its prefix were
+/// replaced with `new_prefix`, preserving everything after the prefix.
+fn bumped_name(migration: &Migration, new_prefix: &str) -> String {
+    let rest = migration
+        .name
+        .strip_prefix(&migration.prefix)
+        .unwrap_or(&migration.name);
+    format!("{}{}", new_prefix, rest)
+}
+
+/// Runs `validate` against `path` and prints any issues found.
+/// Returns `true` if the history is clean.
+fn validate_and_report(path: &str) -> Result<bool, MigrationError> {
+    let issues = validate(path)?;
+    if issues.is_empty() {
+        return Ok(true);
+    }
+
+    println!("Found {} problem(s) in migration history:", issues.len());
+    for issue in &issues {
+        println!("  - {}", issue);
+    }
+    Ok(false)
+}
+
+/// Finds the name of the migration that immediately precedes
+/// `migration_to_bump` in sort order, which is the dependency it should
+/// currently declare.
+fn predecessor_name(
+    migrations: &[Migration],
+    migration_to_bump: &Migration,
+) -> Result<String, MigrationError> {
+    let idx = migrations
+        .iter()
+        .position(|x| x.name == migration_to_bump.name)
+        .ok_or_else(|| MigrationError::NotAMigrationFile(migration_to_bump.name.to_owned()))?;
+
+    if idx == 0 {
+        return Err(MigrationError::NoPredecessor(
+            migration_to_bump.name.to_owned(),
+        ));
     }
 
-    migrations
+    if migrations[idx].sort_key > migrations[idx - 1].sort_key {
+        Ok(migrations[idx - 1].name.to_owned())
+    } else if idx >= 2 {
+        Ok(migrations[idx - 2].name.to_owned())
+    } else {
+        Err(MigrationError::NoPredecessor(
+            migration_to_bump.name.to_owned(),
+        ))
+    }
 }
 
+/// Rewrites `migration_to_bump`'s dependency tuple to point at the
+/// migration that is currently last, returning `(old_dependency,
+/// new_dependency)` so the caller can journal the change.
 fn update_dependency(
     path: &str,
-    migrations: Vec<Migration>,
-    migration_to_bump: Migration,
-) -> Result<(), Box<dyn Error>> {
+    migrations: &[Migration],
+    migration_to_bump: &Migration,
+) -> Result<(String, String), MigrationError> {
     let path = [String::from(path), migration_to_bump.name.to_owned()].join("");
     let file_path = Path::new(&path);
     let mut src = File::open(file_path)?;
     let mut contents = String::new();
-    src.read_to_string(&mut contents)
-        .expect("Unable to read the file");
+    src.read_to_string(&mut contents)?;
     drop(src);
 
     let name_of_last_migration = match migrations.last() {
         Some(m) => m.name.to_owned(),
-        None => return Err("No last migration found.".into()),
+        None => {
+            return Err(MigrationError::DependencyNotFound {
+                migration: migration_to_bump.name.to_owned(),
+                predecessor: String::from("<none>"),
+            })
+        }
     };
 
-    let idx_of_migration_to_bump = match migrations
-        .iter()
-        .position(|x| x.name == migration_to_bump.name)
-    {
-        Some(idx) => idx,
-        None => return Err("Migration idx not found".into()),
-    };
+    let name_of_before_migration = predecessor_name(migrations, migration_to_bump)?;
 
-    let name_of_before_migration;
-    if migrations[idx_of_migration_to_bump].number > migrations[idx_of_migration_to_bump - 1].number
-    {
-        name_of_before_migration = migrations[idx_of_migration_to_bump - 1].name.to_owned();
-    } else {
-        name_of_before_migration = migrations[idx_of_migration_to_bump - 2].name.to_owned();
-    }
+    let new_data = rewrite_dependency(
+        &contents,
+        &migration_to_bump.name,
+        &name_of_before_migration,
+        &name_of_last_migration,
+    )?;
 
-    let new_data = contents.replace(
-        &*(name_of_before_migration.replace(".py", "")),
-        &*(name_of_last_migration).replace(".py", ""),
-    );
-
-    let mut dst = File::create(&file_path)?;
-    match dst.write(new_data.as_bytes()) {
-        Ok(_) => Ok(()),
-        Err(_) => Err("Could not write to file".into()),
-    }
+    let mut dst = File::create(file_path)?;
+    dst.write(new_data.as_bytes())
+        .map_err(|e| MigrationError::WriteFailed(migration_to_bump.name.to_owned(), e))?;
+    Ok((name_of_before_migration, name_of_last_migration))
 }
 
-fn update_name(path: &str, name_before: &str, name_after: &str) -> Result<(), std::io::Error> {
-    return fs::rename(
-        format!("{}{}", path, name_before),
-        format!("{}{}", path, name_after),
-    );
-}
-
-fn bump_migration(path: &str, migration_name: &str) -> () {
-    let mut migrations = collect_migrations(path);
+fn bump_migration(path: &str, migration_name: &str, dry_run: bool) -> Result<(), MigrationError> {
+    let mut migrations = collect_migrations(path)?;
 
     // Sort the migrations in ascending migration number order.
-    migrations.sort_by(|a, b| a.number.cmp(&b.number));
+    migrations.sort_by_key(|m| m.sort_key);
 
-    let migration_to_bump: Migration = Migration {
+    let prefix = migration_name.split("_").next().unwrap_or("");
+    let migration_to_bump = Migration {
+        prefix: String::from(prefix),
+        sort_key: prefix
+            .parse::<i64>()
+            .map_err(|_| MigrationError::MigrationNumberUnparseable(String::from(migration_name)))?,
         name: String::from(migration_name),
-        number: migration_name
-            .split("_")
-            .nth(0)
-            .unwrap()
-            .parse::<i32>()
-            .unwrap(),
     };
 
-    // Generate the new "bumped" name.
-    let bumped_name = migration_to_bump.name.clone().replace(
-        &migration_to_bump.number.to_string(),
-        &(migrations.last().unwrap().number + 1).to_string(),
-    );
+    let new_prefix = next_prefix(&migrations)
+        .ok_or_else(|| MigrationError::NotAMigrationFile(String::from(migration_name)))?;
+
+    // Generate the new "bumped" name, preserving the format and padding of
+    // the existing highest-numbered migration.
+    let bumped_name = bumped_name(&migration_to_bump, &new_prefix);
+
+    if dry_run {
+        let name_of_before_migration = predecessor_name(&migrations, &migration_to_bump)?;
+        let name_of_last_migration = migrations.last().unwrap().name.clone();
+        println!(
+            "[dry-run] would rename {:?} to {:?}",
+            migration_to_bump.name, bumped_name
+        );
+        println!(
+            "[dry-run] would rewrite dependency {:?} to {:?}",
+            name_of_before_migration, name_of_last_migration
+        );
+        return Ok(());
+    }
 
     print!(
         "Bumping migration: {:?}   🤜 🤜 🤜 🤜 🤜 🤜 🤜 🤜 🤜 🤜   {:?}",
         migration_to_bump.name, bumped_name
     );
 
-    let dependency_update = match update_dependency(path, migrations, migration_to_bump.to_owned())
-    {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e),
-    };
+    let (old_dependency, new_dependency) =
+        match update_dependency(path, &migrations, &migration_to_bump) {
+            Ok(dependencies) => dependencies,
+            Err(e) => {
+                println!();
+                println!("Failed to update dependency, terminating ... ❌");
+                return Err(e);
+            }
+        };
 
-    if dependency_update.is_err() {
-        println!("{:?}", dependency_update);
+    if let Err(e) = rename_migration_file(path, &migration_to_bump.name, &bumped_name) {
+        println!(" ❌");
+        return Err(e);
     }
 
-    let succesful_dependency_update = dependency_update.is_ok();
+    append_journal_entry(
+        path,
+        &JournalEntry {
+            original_name: migration_to_bump.name.clone(),
+            new_name: bumped_name,
+            old_dependency,
+            new_dependency,
+        },
+    )?;
 
-    if !succesful_dependency_update {
-        println!("");
-        println!("Failed to update dependency, terminating ... ❌");
-        return;
+    println!(" ✅");
+    Ok(())
+}
+
+fn bump(path: &str, migrations_to_bump: Vec<String>, dry_run: bool) -> Result<(), MigrationError> {
+    if !validate_and_report(path)? {
+        println!("Refusing to bump into an already-inconsistent migration history.");
+        return Err(MigrationError::InconsistentHistory);
     }
 
-    let succesful_name_update = match update_name(path, &migration_to_bump.name, &bumped_name) {
-        Ok(_) => true,
-        Err(_) => false,
-    };
+    let mut failed = vec![];
+    for migration in &migrations_to_bump {
+        if let Err(e) = bump_migration(path, migration, dry_run) {
+            failed.push((migration.clone(), e));
+        }
+    }
 
-    match succesful_dependency_update && succesful_name_update {
-        true => println!(" ✅"),
-        false => println!(" ❌"),
-    };
-}
+    if !dry_run {
+        println!();
+        println!(
+            "{}/{} migration(s) bumped successfully.",
+            migrations_to_bump.len() - failed.len(),
+            migrations_to_bump.len()
+        );
+    }
+    for (migration, error) in &failed {
+        println!("  - {} failed: {}", migration, error);
+    }
 
-fn bump(path: &str, migrations_to_bump: Vec<String>) -> () {
-    for migration in migrations_to_bump {
-        bump_migration(path, &migration[..]);
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed.into_iter().next().unwrap().1)
     }
 }
 
 fn main() {
-    set_hook(Box::new(|info| {
-        if let Some(s) = info.payload().downcast_ref::<String>() {
-            println!("{}", s);
+    let matches = cli().get_matches();
+    let dry_run = matches.get_flag("dry_run");
+
+    let result = match matches.subcommand() {
+        Some(("bump", sub)) => {
+            let dir_path = sub.get_one::<String>("dir_path").unwrap();
+            let migration_names: Vec<String> = sub
+                .get_many::<String>("migration_names")
+                .unwrap()
+                .cloned()
+                .collect();
+            bump(dir_path, migration_names, dry_run)
         }
-    }));
-    let args: Vec<String> = env::args().collect();
-    if args.len() <= 1 {
-        println!("{}", INCORRECT_USAGE_MESSAGE);
-        return;
-    }
-    let first_arg = &args[1];
-    if first_arg == "-h" || first_arg == "--help" {
-        println!("{}", HELP_MESSAGE);
-    } else if args.len() >= 3 {
-        let path = &args[1];
-        let migrations_to_bump = args[2..].to_vec();
-        bump(path, migrations_to_bump);
-    } else {
-        println!("{}", INCORRECT_USAGE_MESSAGE);
+        Some(("list", sub)) => list(sub.get_one::<String>("dir_path").unwrap()),
+        Some(("validate", sub)) => {
+            match validate_and_report(sub.get_one::<String>("dir_path").unwrap()) {
+                Ok(true) => Ok(()),
+                Ok(false) => std::process::exit(1),
+                Err(e) => Err(e),
+            }
+        }
+        Some(("undo", sub)) => undo(
+            sub.get_one::<String>("dir_path").unwrap(),
+            sub.get_one::<String>("bumped_name").unwrap(),
+        ),
+        _ => unreachable!("subcommand_required(true) guarantees a match"),
+    };
+
+    if let Err(e) = result {
+        println!("Error: {}", e);
+        std::process::exit(1);
     }
 }